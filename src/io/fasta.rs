@@ -6,6 +6,14 @@
 
 //! FASTA format reading and writing.
 //!
+//! Reading and writing transparently support gzip, bzip2, xz and zstd compressed
+//! streams via `Reader::new_auto`/`Reader::from_file_with_compression` and
+//! `Writer::with_compression`/`Writer::to_file_with_compression`. This pulls in
+//! `flate2`, `bzip2`, `xz2` and `zstd` as dependencies; the pre-1.0 API used here
+//! (`flate2::Compression::Default`, `bzip2::Compression::Default`,
+//! `zstd::stream::Encoder::new(writer, level)?.auto_finish()`) needs roughly
+//! `flate2 ^0.2`, `bzip2 ^0.3`, `xz2 ^0.1`, `zstd ^0.4` in `Cargo.toml`.
+//!
 //! # Example
 //!
 //! ```
@@ -18,13 +26,18 @@
 use std::io;
 use std::io::prelude::*;
 use std::ascii::AsciiExt;
-use std::collections;
 use std::fs;
 use std::path::Path;
 use std::convert::AsRef;
 use std::cmp::min;
+use std::str;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
-use csv;
+use flate2;
+use bzip2;
+use xz2;
+use zstd;
 
 use utils::{TextSlice, Text};
 
@@ -32,6 +45,93 @@ use utils::{TextSlice, Text};
 /// Maximum size of temporary buffer used for reading indexed FASTA files.
 const MAX_FASTA_BUFFER_SIZE: usize = 512;
 
+/// Number of leading bytes needed to recognize any of the supported magic numbers.
+const MAGIC_BYTES: usize = 6;
+
+
+/// Compression format of a FASTA stream, either sniffed from its magic number on
+/// read or chosen from a file extension (or explicitly) on write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    None,
+}
+
+
+impl Compression {
+    /// Determine the compression format from the leading bytes of a stream.
+    fn from_magic(buf: &[u8]) -> Self {
+        if buf.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if buf.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if buf.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]) {
+            Compression::Xz
+        } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Determine the compression format from a file path's extension.
+    fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("bz2") => Compression::Bzip2,
+            Some("xz") => Compression::Xz,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wrap `reader` in the decoder matching this compression format.
+    fn decoder<R: io::Read + 'static>(&self, reader: R) -> io::Result<Box<io::Read>> {
+        Ok(match *self {
+               Compression::Gzip => Box::new(flate2::read::GzDecoder::new(reader)?),
+               Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+               Compression::Xz => Box::new(xz2::read::XzDecoder::new(reader)),
+               Compression::Zstd => Box::new(zstd::stream::Decoder::new(reader)?),
+               Compression::None => Box::new(reader),
+           })
+    }
+
+    /// Wrap `writer` in the encoder matching this compression format.
+    fn encoder<W: io::Write + 'static>(&self, writer: W) -> io::Result<Box<io::Write>> {
+        Ok(match *self {
+               Compression::Gzip => {
+                   Box::new(flate2::write::GzEncoder::new(writer, flate2::Compression::Default))
+               }
+               Compression::Bzip2 => {
+                   Box::new(bzip2::write::BzEncoder::new(writer, bzip2::Compression::Default))
+               }
+               Compression::Xz => Box::new(xz2::write::XzEncoder::new(writer, 6)),
+               Compression::Zstd => Box::new(zstd::stream::Encoder::new(writer, 0)?.auto_finish()),
+               Compression::None => Box::new(writer),
+           })
+    }
+}
+
+
+/// Peek up to `buf_len` bytes from `reader` without losing them: the bytes read are
+/// returned chained in front of the reader so that a subsequent full read sees them again.
+fn sniff<R: io::Read>(mut reader: R, buf_len: usize) -> io::Result<(Vec<u8>, io::Chain<io::Cursor<Vec<u8>>, R>)> {
+    let mut buf = vec![0u8; buf_len];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    buf.truncate(filled);
+    let chained = io::Cursor::new(buf.clone()).chain(reader);
+    Ok((buf, chained))
+}
+
 
 /// A FASTA reader.
 pub struct Reader<R: io::Read> {
@@ -48,6 +148,28 @@ impl Reader<fs::File> {
 }
 
 
+impl Reader<Box<io::Read>> {
+    /// Read FASTA from given file path, transparently decompressing gzip, bzip2, xz or zstd
+    /// input based on the stream's magic bytes (plain FASTA falls through unchanged).
+    pub fn from_file_with_compression<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Reader::new_auto(fs::File::open(path)?)
+    }
+
+    /// Wrap `reader`, sniffing its first bytes to auto-detect gzip, bzip2, xz or zstd
+    /// compression and decompressing transparently if found.
+    pub fn new_auto<R: io::Read + 'static>(reader: R) -> io::Result<Self> {
+        let (magic, chained) = sniff(reader, MAGIC_BYTES)?;
+        let compression = Compression::from_magic(&magic);
+        Ok(Reader::new(compression.decoder(chained)?))
+    }
+
+    /// Wrap `reader`, assuming it is gzip-compressed.
+    pub fn new_gzip<R: io::Read + 'static>(reader: R) -> io::Result<Self> {
+        Ok(Reader::new(Compression::Gzip.decoder(reader)?))
+    }
+}
+
+
 impl<R: io::Read> Reader<R> {
     /// Create a new Fasta reader given an instance of `io::Read`.
     pub fn new(reader: R) -> Self {
@@ -92,40 +214,48 @@ impl<R: io::Read> Reader<R> {
 
 /// A FASTA index as created by SAMtools (.fai).
 pub struct Index {
-    inner: collections::HashMap<String, IndexRecord>,
-    seqs: Vec<String>,
+    inner: HashMap<String, IndexRecord>,
 }
 
 
 impl Index {
-    /// Open a FASTA index from a given `io::Read` instance.
-    pub fn new<R: io::Read>(fai: R) -> csv::Result<Self> {
-        let mut inner = collections::HashMap::new();
-        let mut seqs = vec![];
-        let mut fai_reader = csv::Reader::from_reader(fai)
-            .delimiter(b'\t')
-            .has_headers(false);
-        for row in fai_reader.decode() {
-            let (name, record): (String, IndexRecord) = try!(row);
-            seqs.push(name.clone());
+    /// Open a FASTA index from a given `io::Read` instance. Parses the tab-delimited `.fai`
+    /// format by hand (rather than via a `csv` crate) so it has no dependency beyond `io`.
+    pub fn new<R: io::Read>(fai: R) -> io::Result<Self> {
+        let mut reader = io::BufReader::new(fai);
+        let mut inner = HashMap::new();
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let fields = strip_terminator(&line);
+            if fields.is_empty() {
+                continue;
+            }
+
+            let mut fields = fields.split(|&b| b == b'\t');
+            let name = fields.next()
+                .and_then(|f| str::from_utf8(f).ok())
+                .map(|f| f.to_owned())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Invalid .fai line: missing name"))?;
+            let record = parse_fai_record(&mut fields)?;
+
             inner.insert(name, record);
         }
-        Ok(Index {
-               inner: inner,
-               seqs: seqs,
-           })
+
+        Ok(Index { inner: inner })
     }
 
     /// Open a FASTA index from a given file path.
-    pub fn from_file<P: AsRef<Path>>(path: &P) -> csv::Result<Self> {
-        match fs::File::open(path) {
-            Ok(fai) => Self::new(fai),
-            Err(e) => Err(csv::Error::Io(e)),
-        }
+    pub fn from_file<P: AsRef<Path>>(path: &P) -> io::Result<Self> {
+        Self::new(fs::File::open(path)?)
     }
 
     /// Open a FASTA index given the corresponding FASTA file path (e.g. for ref.fasta we expect ref.fasta.fai).
-    pub fn with_fasta_file<P: AsRef<Path>>(fasta_path: &P) -> csv::Result<Self> {
+    pub fn with_fasta_file<P: AsRef<Path>>(fasta_path: &P) -> io::Result<Self> {
         let mut fai_path = fasta_path.as_ref().as_os_str().to_owned();
         fai_path.push(".fai");
 
@@ -134,46 +264,207 @@ impl Index {
 
     /// Return a vector of sequences described in the index.
     pub fn sequences(&self) -> Vec<Sequence> {
-        self.seqs
+        self.inner
             .iter()
-            .map(|name| {
+            .map(|(name, record)| {
                      Sequence {
                          name: name.clone(),
-                         len: self.inner[name].len,
+                         len: record.len,
                      }
                  })
             .collect()
     }
+
+    /// Return the `IndexRecord` for the given sequence name, if present.
+    fn get(&self, seqname: &str) -> Option<&IndexRecord> {
+        self.inner.get(seqname)
+    }
+}
+
+
+/// Parse the four tab-separated integer fields (`len`, `offset`, `line_bases`, `line_bytes`)
+/// of a `.fai` line.
+fn parse_fai_record<'a, I: Iterator<Item = &'a [u8]>>(fields: &mut I) -> io::Result<IndexRecord> {
+    fn next_u64<'a, I: Iterator<Item = &'a [u8]>>(fields: &mut I) -> io::Result<u64> {
+        fields.next()
+            .and_then(|f| str::from_utf8(f).ok())
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Invalid .fai line: expected integer field"))
+    }
+
+    Ok(IndexRecord {
+           len: next_u64(fields)?,
+           offset: next_u64(fields)?,
+           line_bases: next_u64(fields)?,
+           line_bytes: next_u64(fields)?,
+       })
+}
+
+
+/// Create a SAMtools-compatible `.fai` index for the FASTA file at `fasta_path`, writing it
+/// next to the FASTA file (e.g. for `ref.fasta` this writes `ref.fasta.fai`).
+pub fn index<P: AsRef<Path>>(fasta_path: P) -> io::Result<()> {
+    let fasta_path = fasta_path.as_ref();
+    let records = index_records(fs::File::open(fasta_path)?)?;
+
+    let mut fai_path = fasta_path.as_os_str().to_owned();
+    fai_path.push(".fai");
+    write_fai(&fai_path, &records)
+}
+
+
+/// Stream `fasta`, recording `len`/`offset`/`line_bases`/`line_bytes` for each record,
+/// the same invariants `IndexRecord` encodes. Errors if the interior lines of a record
+/// (all but the last) are not all the same length.
+fn index_records<R: io::Read>(fasta: R) -> io::Result<Vec<(String, IndexRecord)>> {
+    let mut reader = io::BufReader::new(fasta);
+    let mut records = Vec::new();
+    let mut current: Option<(String, IndexRecord)> = None;
+    let mut prev_line_bases = None;
+    let mut offset = 0u64;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let line_bytes = line.len() as u64;
+
+        if line.starts_with(b">") {
+            if let Some(finished) = current.take() {
+                check_last_line_bases(&finished.1, prev_line_bases)?;
+                records.push(finished);
+            }
+            let name = String::from_utf8_lossy(&line[1..])
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_owned();
+            offset += line_bytes;
+            current = Some((name,
+                             IndexRecord {
+                                 len: 0,
+                                 offset: offset,
+                                 line_bases: 0,
+                                 line_bytes: 0,
+                             }));
+            prev_line_bases = None;
+        } else {
+            let record = &mut current
+                               .as_mut()
+                               .ok_or_else(|| {
+                                   io::Error::new(io::ErrorKind::Other,
+                                                  "FASTA record must start with '>'")
+                               })?
+                               .1;
+            let line_bases = strip_terminator(&line).len() as u64;
+
+            if let Some(prev) = prev_line_bases {
+                if prev != record.line_bases {
+                    return Err(io::Error::new(io::ErrorKind::Other,
+                                              "Inconsistent line length within FASTA record"));
+                }
+            }
+            if record.line_bases == 0 {
+                record.line_bases = line_bases;
+                record.line_bytes = line_bytes;
+            }
+            record.len += line_bases;
+            prev_line_bases = Some(line_bases);
+            offset += line_bytes;
+        }
+    }
+    if let Some(finished) = current.take() {
+        check_last_line_bases(&finished.1, prev_line_bases)?;
+        records.push(finished);
+    }
+
+    Ok(records)
+}
+
+/// The last sequence line of a record may be shorter than `line_bases` (a partial final
+/// line), but never longer, or the `.fai` would describe a layout `samtools faidx` rejects.
+fn check_last_line_bases(record: &IndexRecord, last_line_bases: Option<u64>) -> io::Result<()> {
+    if let Some(last) = last_line_bases {
+        if last > record.line_bases {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      "Inconsistent line length within FASTA record"));
+        }
+    }
+    Ok(())
+}
+
+
+/// Strip a trailing `\n` or `\r\n` from a line read by `BufRead::read_until`.
+fn strip_terminator(line: &[u8]) -> &[u8] {
+    let len = line.len();
+    if len >= 2 && line[len - 2] == b'\r' && line[len - 1] == b'\n' {
+        &line[..len - 2]
+    } else if len >= 1 && (line[len - 1] == b'\n' || line[len - 1] == b'\r') {
+        &line[..len - 1]
+    } else {
+        line
+    }
+}
+
+
+/// Write `records` out in the tab-delimited `.fai` format.
+fn write_fai<P: AsRef<Path>>(fai_path: P, records: &[(String, IndexRecord)]) -> io::Result<()> {
+    let mut fai = fs::File::create(fai_path)?;
+    for &(ref name, ref record) in records {
+        writeln!(fai,
+                 "{}\t{}\t{}\t{}\t{}",
+                 name,
+                 record.len,
+                 record.offset,
+                 record.line_bases,
+                 record.line_bytes)?;
+    }
+
+    Ok(())
 }
 
 
 /// A FASTA reader with an index as created by SAMtools (.fai).
+///
+/// Reading is decoupled into two steps, mirroring the htslib/faidx workflow: `fetch`
+/// (or `fetch_all`) selects which interval to read and seeks to it, then `read`/`read_iter`
+/// pull the bytes of the most recently fetched interval.
 pub struct IndexedReader<R: io::Read + io::Seek> {
     reader: io::BufReader<R>,
     pub index: Index,
+    fetched: Option<Fetched>,
+}
+
+
+/// The interval selected by `IndexedReader::fetch`, pending a `read`/`read_iter` call.
+struct Fetched {
+    record: IndexRecord,
+    bases_left: u64,
+    line_offset: u64,
 }
 
 
 impl IndexedReader<fs::File> {
     /// Read from a given file path. This assumes the index ref.fasta.fai to be present for FASTA ref.fasta.
-    pub fn from_file<P: AsRef<Path>>(path: &P) -> csv::Result<Self> {
+    pub fn from_file<P: AsRef<Path>>(path: &P) -> io::Result<Self> {
         let index = try!(Index::with_fasta_file(path));
+        let fasta = try!(fs::File::open(path));
 
-        match fs::File::open(path) {
-            Ok(fasta) => Ok(IndexedReader::with_index(fasta, index)),
-            Err(e) => Err(csv::Error::Io(e)),
-        }
+        Ok(IndexedReader::with_index(fasta, index))
     }
 }
 
 
 impl<R: io::Read + io::Seek> IndexedReader<R> {
     /// Read from a FASTA and its index, both given as `io::Read`. FASTA has to be `io::Seek` in addition.
-    pub fn new<I: io::Read>(fasta: R, fai: I) -> csv::Result<Self> {
+    pub fn new<I: io::Read>(fasta: R, fai: I) -> io::Result<Self> {
         let index = try!(Index::new(fai));
         Ok(IndexedReader {
                reader: io::BufReader::new(fasta),
                index: index,
+               fetched: None,
            })
     }
 
@@ -182,88 +473,118 @@ impl<R: io::Read + io::Seek> IndexedReader<R> {
         IndexedReader {
             reader: io::BufReader::new(fasta),
             index: index,
+            fetched: None,
         }
     }
 
-    /// For a given seqname, read the whole sequence into the given vector.
-    pub fn read_all(&mut self, seqname: &str, seq: &mut Text) -> io::Result<()> {
+    /// Select the given interval of the given seqname (stop position exclusive) for reading,
+    /// seeking to its start. A subsequent call to `read` or `read_iter` pulls out its bytes.
+    pub fn fetch(&mut self, seqname: &str, start: u64, stop: u64) -> io::Result<()> {
         let idx = self.idx(seqname)?;
 
-        self.read_into_buffer(&idx, 0, idx.len, seq)
-    }
+        if stop > idx.len {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      "FASTA read interval was out of bounds"));
+        } else if start > stop {
+            return Err(io::Error::new(io::ErrorKind::Other, "Invalid query interval"));
+        }
 
-    /// Read the given interval of the given seqname into the given vector (stop position is exclusive).
-    pub fn read(&mut self, seqname: &str, start: u64, stop: u64, seq: &mut Text) -> io::Result<()> {
-        let idx = self.idx(seqname)?;
+        let line_offset = self.seek_to(&idx, start)?;
+        self.fetched = Some(Fetched {
+                                record: idx,
+                                bases_left: stop - start,
+                                line_offset: line_offset,
+                            });
 
-        self.read_into_buffer(&idx, start, stop, seq)
+        Ok(())
     }
 
-
-    /// For a given seqname, return an iterator yielding that sequence.
-    pub fn read_iter_all(&mut self, seqname: &str)
-                -> io::Result<IndexedReaderIterator<R>> {
+    /// Select the whole sequence of the given seqname for reading.
+    pub fn fetch_all(&mut self, seqname: &str) -> io::Result<()> {
         let idx = self.idx(seqname)?;
 
-       self.read_into_iter(idx, 0, idx.len)
-     }
-
-    /// Read the given interval of the given seqname into the given vector (stop position is exclusive).
-    pub fn read_iter(&mut self, seqname: &str, start: u64, stop: u64)
-                -> io::Result<IndexedReaderIterator<R>> {
-        let idx = self.idx(seqname)?;
-
-        self.read_into_iter(idx, start, stop)
+        self.fetch(seqname, 0, idx.len)
     }
 
-    fn read_into_buffer(&mut self, idx: &IndexRecord, start: u64, stop: u64, seq: &mut Text) -> io::Result<()> {
-        if stop > idx.len {
-            return Err(io::Error::new(io::ErrorKind::Other,
-                                      "FASTA read interval was out of bounds"));
-        } else if start > stop {
-            return Err(io::Error::new(io::ErrorKind::Other, "Invalid query interval"));
-        }
-
-        let mut bases_left = stop - start;
-        let mut line_offset = self.seek_to(&idx, start)?;
-        let mut buf = vec![0u8; Self::buffer_size(&idx, bases_left, line_offset)];
+    /// Read the interval previously selected with `fetch`/`fetch_all` into the given vector.
+    pub fn read(&mut self, seq: &mut Text) -> io::Result<()> {
+        let mut fetched = self.fetched
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No sequence fetched for reading."))?;
+        let mut buf = vec![0u8; Self::buffer_size(&fetched.record, fetched.bases_left, fetched.line_offset)];
 
         seq.clear();
-        while bases_left > 0 {
-            let bases_read = self.read_line(&idx, &mut line_offset, bases_left, &mut buf)?;
+        while fetched.bases_left > 0 {
+            let bases_read = self.read_line(&fetched.record, &mut fetched.line_offset, fetched.bases_left, &mut buf)?;
 
             seq.extend_from_slice(&buf[..bases_read as usize]);
-            bases_left -= bases_read;
+            fetched.bases_left -= bases_read;
         }
 
         Ok(())
     }
 
-    fn read_into_iter(&mut self, idx: IndexRecord, start: u64, stop: u64)
-                -> io::Result<IndexedReaderIterator<R>> {
-        if stop > idx.len {
-            return Err(io::Error::new(io::ErrorKind::Other,
-                                      "FASTA read interval was out of bounds"));
-        } else if start > stop {
-            return Err(io::Error::new(io::ErrorKind::Other, "Invalid query interval"));
-        }
-
-        let line_offset = self.seek_to(&idx, start)?;
+    /// Return an iterator over the interval previously selected with `fetch`/`fetch_all`.
+    pub fn read_iter(&mut self) -> io::Result<IndexedReaderIterator<R>> {
+        let fetched = self.fetched
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No sequence fetched for reading."))?;
+        let buf_len = Self::buffer_size(&fetched.record, fetched.bases_left, fetched.line_offset);
 
         Ok(IndexedReaderIterator {
             reader: self,
-            record: idx,
-            bases_left: stop - start,
-            line_offset: line_offset,
-            buf: vec![0u8; Self::buffer_size(&idx, stop - start, line_offset)],
+            record: fetched.record,
+            bases_left: fetched.bases_left,
+            line_offset: fetched.line_offset,
+            buf: vec![0u8; buf_len],
             buf_len: 0,
             buf_idx: 0,
         })
     }
 
+    /// Read the given interval of the given seqname (stop position exclusive) into `seq`.
+    /// Convenience one-shot wrapper around `fetch` + `read` for callers who don't need to
+    /// split "where to read" from "pull the bytes".
+    pub fn read_region(&mut self, seqname: &str, start: u64, stop: u64, seq: &mut Text) -> io::Result<()> {
+        self.fetch(seqname, start, stop)?;
+        self.read(seq)
+    }
+
+    /// Read the whole sequence of the given seqname into `seq`.
+    pub fn read_region_all(&mut self, seqname: &str, seq: &mut Text) -> io::Result<()> {
+        self.fetch_all(seqname)?;
+        self.read(seq)
+    }
+
+    /// Return an iterator over the given interval of the given seqname (stop position exclusive).
+    pub fn read_region_iter(&mut self, seqname: &str, start: u64, stop: u64) -> io::Result<IndexedReaderIterator<R>> {
+        self.fetch(seqname, start, stop)?;
+        self.read_iter()
+    }
+
+    /// Return an iterator over the whole sequence of the given seqname.
+    pub fn read_region_iter_all(&mut self, seqname: &str) -> io::Result<IndexedReaderIterator<R>> {
+        self.fetch_all(seqname)?;
+        self.read_iter()
+    }
+
+    /// Deprecated alias for `read_region_all`, kept for code written against the pre-`fetch`/
+    /// `read` one-shot API.
+    #[deprecated(note = "use read_region_all instead")]
+    pub fn read_all(&mut self, seqname: &str, seq: &mut Text) -> io::Result<()> {
+        self.read_region_all(seqname, seq)
+    }
+
+    /// Deprecated alias for `read_region_iter_all`, kept for code written against the pre-`fetch`/
+    /// `read` one-shot API.
+    #[deprecated(note = "use read_region_iter_all instead")]
+    pub fn read_iter_all(&mut self, seqname: &str) -> io::Result<IndexedReaderIterator<R>> {
+        self.read_region_iter_all(seqname)
+    }
+
     /// Return the IndexRecord for the given sequence name or io::Result::Err
     fn idx(&self, seqname: &str) -> io::Result<IndexRecord> {
-        match self.index.inner.get(seqname) {
+        match self.index.get(seqname) {
             Some(idx) => Ok(idx.clone()),
             None => Err(io::Error::new(io::ErrorKind::Other, "Unknown sequence name.")),
         }
@@ -329,7 +650,7 @@ impl<R: io::Read + io::Seek> IndexedReader<R> {
 
 
 /// Record of a FASTA index.
-#[derive(RustcDecodable, Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 struct IndexRecord {
     len: u64,
     offset: u64,
@@ -403,6 +724,7 @@ impl<'a, R: io::Read + io::Seek + 'a> Iterator for IndexedReaderIterator<'a, R>
 /// A Fasta writer.
 pub struct Writer<W: io::Write> {
     writer: io::BufWriter<W>,
+    line_width: usize,
 }
 
 
@@ -414,10 +736,36 @@ impl Writer<fs::File> {
 }
 
 
+impl Writer<Box<io::Write>> {
+    /// Write to the given file path, compressing the output according to its extension
+    /// (`.gz`, `.bz2`, `.xz` or `.zst`); any other extension writes plain FASTA.
+    pub fn to_file_with_compression<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let compression = Compression::from_extension(&path);
+        Writer::with_compression(fs::File::create(path)?, compression)
+    }
+
+    /// Wrap `writer`, compressing its output with the given `Compression` format.
+    pub fn with_compression<W: io::Write + 'static>(writer: W, compression: Compression) -> io::Result<Self> {
+        Ok(Writer::new(compression.encoder(writer)?))
+    }
+}
+
+
 impl<W: io::Write> Writer<W> {
-    /// Create a new Fasta writer.
+    /// Create a new Fasta writer. Sequences are written unwrapped, on a single line, unless
+    /// `line_width` is used to configure wrapping.
     pub fn new(writer: W) -> Self {
-        Writer { writer: io::BufWriter::new(writer) }
+        Writer {
+            writer: io::BufWriter::new(writer),
+            line_width: 0,
+        }
+    }
+
+    /// Wrap written sequences to `line_width` residues per line. `0` (the default) disables
+    /// wrapping and writes the whole sequence on one line.
+    pub fn line_width(mut self, line_width: usize) -> Self {
+        self.line_width = line_width;
+        self
     }
 
     /// Directly write a Fasta record.
@@ -427,15 +775,127 @@ impl<W: io::Write> Writer<W> {
 
     /// Write a Fasta record with given id, optional description and sequence.
     pub fn write(&mut self, id: &str, desc: Option<&str>, seq: TextSlice) -> io::Result<()> {
-        try!(self.writer.write_all(b">"));
-        try!(self.writer.write_all(id.as_bytes()));
-        if desc.is_some() {
-            try!(self.writer.write_all(b" "));
-            try!(self.writer.write_all(desc.unwrap().as_bytes()));
+        write_header(&mut self.writer, id, desc)?;
+        write_wrapped(&mut self.writer, seq, self.line_width)?;
+
+        Ok(())
+    }
+
+    /// Flush the writer, ensuring that everything is written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+/// Write a Fasta header line (`>id desc\n`), shared by `Writer::write` and
+/// `IndexedWriter::write` so the two can't drift. Returns the number of bytes written.
+fn write_header<W: io::Write>(writer: &mut W, id: &str, desc: Option<&str>) -> io::Result<u64> {
+    try!(writer.write_all(b">"));
+    try!(writer.write_all(id.as_bytes()));
+    if let Some(desc) = desc {
+        try!(writer.write_all(b" "));
+        try!(writer.write_all(desc.as_bytes()));
+    }
+    try!(writer.write_all(b"\n"));
+
+    Ok(1 + id.len() as u64 + desc.map_or(0, |desc| 1 + desc.len() as u64) + 1)
+}
+
+
+/// Write `seq` to `writer`, wrapped to `line_width` residues per line (`0` disables
+/// wrapping and writes it as a single line). Every line, including the last, is
+/// newline-terminated. Returns the number of bytes written.
+fn write_wrapped<W: io::Write>(writer: &mut W, seq: TextSlice, line_width: usize) -> io::Result<u64> {
+    if line_width == 0 {
+        writer.write_all(seq)?;
+        writer.write_all(b"\n")?;
+        return Ok(seq.len() as u64 + 1);
+    }
+
+    let mut written = 0u64;
+    for chunk in seq.chunks(line_width) {
+        writer.write_all(chunk)?;
+        writer.write_all(b"\n")?;
+        written += chunk.len() as u64 + 1;
+    }
+    if seq.is_empty() {
+        writer.write_all(b"\n")?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+
+/// The `line_bases`/`line_bytes` a `.fai` index would record for a sequence of `seq_len`
+/// residues written with the given `line_width` (see `write_wrapped`).
+fn wrapped_dims(seq_len: usize, line_width: usize) -> (u64, u64) {
+    if line_width == 0 || seq_len <= line_width {
+        (seq_len as u64, seq_len as u64 + 1)
+    } else {
+        (line_width as u64, line_width as u64 + 1)
+    }
+}
+
+
+/// A `Writer` that additionally builds a SAMtools-compatible `.fai` index of the records it
+/// writes, so a freshly produced FASTA can be indexed in the same pass instead of a second
+/// one via `index`.
+pub struct IndexedWriter<W: io::Write> {
+    writer: Writer<W>,
+    offset: u64,
+    records: Vec<(String, IndexRecord)>,
+}
+
+
+impl IndexedWriter<fs::File> {
+    /// Write to the given file path, building an index as records are written.
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::File::create(path).map(IndexedWriter::new)
+    }
+}
+
+
+impl<W: io::Write> IndexedWriter<W> {
+    /// Create a new indexing Fasta writer.
+    pub fn new(writer: W) -> Self {
+        IndexedWriter {
+            writer: Writer::new(writer),
+            offset: 0,
+            records: Vec::new(),
         }
-        try!(self.writer.write_all(b"\n"));
-        try!(self.writer.write_all(seq));
-        try!(self.writer.write_all(b"\n"));
+    }
+
+    /// Wrap written sequences to `line_width` residues per line, see `Writer::line_width`.
+    pub fn line_width(mut self, line_width: usize) -> Self {
+        self.writer = self.writer.line_width(line_width);
+        self
+    }
+
+    /// Directly write a Fasta record, recording its position in the index.
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        self.write(record.id().unwrap_or(""), record.desc(), record.seq())
+    }
+
+    /// Write a Fasta record with given id, optional description and sequence, recording its
+    /// position in the index.
+    pub fn write(&mut self, id: &str, desc: Option<&str>, seq: TextSlice) -> io::Result<()> {
+        let header_bytes = write_header(&mut self.writer.writer, id, desc)?;
+        let seq_offset = self.offset + header_bytes;
+
+        let seq_bytes = write_wrapped(&mut self.writer.writer, seq, self.writer.line_width)?;
+        let (line_bases, line_bytes) = wrapped_dims(seq.len(), self.writer.line_width);
+
+        self.records
+            .push((id.to_owned(),
+                    IndexRecord {
+                        len: seq.len() as u64,
+                        offset: seq_offset,
+                        line_bases: line_bases,
+                        line_bytes: line_bytes,
+                    }));
+        self.offset = seq_offset + seq_bytes;
 
         Ok(())
     }
@@ -444,6 +904,11 @@ impl<W: io::Write> Writer<W> {
     pub fn flush(&mut self) -> io::Result<()> {
         self.writer.flush()
     }
+
+    /// Write the `.fai` index built so far to the given path.
+    pub fn write_fai<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_fai(path, &self.records)
+    }
 }
 
 
@@ -524,6 +989,294 @@ impl<R: io::Read> Iterator for Records<R> {
 }
 
 
+/// Initial size of the buffer used by `RefReader` to hold unparsed input.
+const REF_READER_INITIAL_BUFSIZE: usize = 64 * 1024;
+
+
+/// The byte ranges of a single record within the buffer that owns it, used by both
+/// `RefReader` (while scanning) and `RecordSet` (once a batch has been copied out).
+#[derive(Clone, Debug)]
+struct RecordPosition {
+    header: (usize, usize),
+    seq_lines: Vec<(usize, usize)>,
+}
+
+
+/// A FASTA record borrowed from the buffer of a `RecordSet`, avoiding the per-record
+/// `String` allocations that `Reader::read` performs.
+pub struct RefRecord<'a> {
+    buffer: &'a [u8],
+    pos: &'a RecordPosition,
+}
+
+
+impl<'a> RefRecord<'a> {
+    /// Return the id of the record.
+    pub fn id(&self) -> Option<&'a str> {
+        self.header_str().splitn(2, ' ').nth(0)
+    }
+
+    /// Return the description of the record, if present.
+    pub fn desc(&self) -> Option<&'a str> {
+        self.header_str().splitn(2, ' ').nth(1)
+    }
+
+    /// Return the sequence of the record. Borrowed directly from the buffer when the
+    /// sequence spans at most one line, copied into an owned buffer otherwise.
+    pub fn seq(&self) -> Cow<'a, [u8]> {
+        match self.pos.seq_lines.len() {
+            0 => Cow::Borrowed(&self.buffer[0..0]),
+            1 => {
+                let (start, end) = self.pos.seq_lines[0];
+                Cow::Borrowed(&self.buffer[start..end])
+            }
+            _ => {
+                let mut seq = Vec::new();
+                for &(start, end) in &self.pos.seq_lines {
+                    seq.extend_from_slice(&self.buffer[start..end]);
+                }
+                Cow::Owned(seq)
+            }
+        }
+    }
+
+    /// Materialize this borrowed record as an owned `Record`.
+    pub fn to_owned_record(&self) -> Record {
+        let mut record = Record::new();
+        record.header.push('>');
+        record.header.push_str(self.header_str());
+        for &(start, end) in &self.pos.seq_lines {
+            record.seq.push_str(str::from_utf8(&self.buffer[start..end]).unwrap_or(""));
+        }
+        record
+    }
+
+    fn header_str(&self) -> &'a str {
+        let (start, end) = self.pos.header;
+        str::from_utf8(&self.buffer[start..end]).unwrap_or("")
+    }
+}
+
+
+/// A batch of FASTA records parsed eagerly into a single owned buffer, so that it can be
+/// handed off to a worker thread without any of the records carrying a borrow of the
+/// `RefReader` that produced them.
+#[derive(Default)]
+pub struct RecordSet {
+    buffer: Vec<u8>,
+    positions: Vec<RecordPosition>,
+}
+
+
+impl RecordSet {
+    /// Create an empty `RecordSet`.
+    pub fn new() -> Self {
+        RecordSet {
+            buffer: Vec::new(),
+            positions: Vec::new(),
+        }
+    }
+
+    /// Number of records currently held by this set.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Whether this set currently holds no records.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Iterate over the records of this set.
+    pub fn iter(&self) -> RecordSetIter {
+        RecordSetIter {
+            set: self,
+            idx: 0,
+        }
+    }
+}
+
+
+/// An iterator over the `RefRecord`s of a `RecordSet`.
+pub struct RecordSetIter<'a> {
+    set: &'a RecordSet,
+    idx: usize,
+}
+
+
+impl<'a> Iterator for RecordSetIter<'a> {
+    type Item = RefRecord<'a>;
+
+    fn next(&mut self) -> Option<RefRecord<'a>> {
+        let pos = self.set.positions.get(self.idx)?;
+        self.idx += 1;
+        Some(RefRecord {
+                 buffer: &self.set.buffer,
+                 pos: pos,
+             })
+    }
+}
+
+
+/// Scan `data` for up to `max_records` complete FASTA records, starting at offset 0.
+/// Returns the positions found and the number of bytes consumed. A record is only
+/// considered complete once the `>` of the following record is seen, unless `eof`
+/// is set, in which case the end of `data` also completes it and a trailing sequence
+/// line with no terminator is accepted.
+fn scan_records(data: &[u8], max_records: usize, eof: bool) -> (Vec<RecordPosition>, usize) {
+    let mut positions = Vec::new();
+    let mut offset = 0;
+
+    while positions.len() < max_records && offset < data.len() {
+        if data[offset] != b'>' {
+            break;
+        }
+
+        let header_start = offset + 1;
+        let header_end = match data[offset..].iter().position(|&b| b == b'\n') {
+            Some(p) => offset + p,
+            None => break,
+        };
+        let header = (header_start, trim_cr(data, header_start, header_end));
+
+        let mut cursor = header_end + 1;
+        let mut seq_lines = Vec::new();
+        let mut complete = false;
+        loop {
+            if cursor >= data.len() {
+                if eof {
+                    complete = true;
+                }
+                break;
+            }
+            if data[cursor] == b'>' {
+                complete = true;
+                break;
+            }
+            match data[cursor..].iter().position(|&b| b == b'\n') {
+                Some(p) => {
+                    let line_end = cursor + p;
+                    let trimmed_end = trim_cr(data, cursor, line_end);
+                    if trimmed_end > cursor {
+                        seq_lines.push((cursor, trimmed_end));
+                    }
+                    cursor = line_end + 1;
+                }
+                None => {
+                    if eof {
+                        if cursor < data.len() {
+                            seq_lines.push((cursor, data.len()));
+                        }
+                        cursor = data.len();
+                        complete = true;
+                    }
+                    break;
+                }
+            }
+        }
+
+        if !complete {
+            break;
+        }
+
+        positions.push(RecordPosition {
+                            header: header,
+                            seq_lines: seq_lines,
+                        });
+        offset = cursor;
+    }
+
+    (positions, offset)
+}
+
+
+/// Trim a single trailing `\r` from the line spanning `[start, end)`.
+fn trim_cr(data: &[u8], start: usize, end: usize) -> usize {
+    if end > start && data[end - 1] == b'\r' {
+        end - 1
+    } else {
+        end
+    }
+}
+
+
+/// A buffered, allocation-amortizing FASTA parser in the style of `seq_io`: instead of
+/// allocating two `String`s per record like `Reader::read`, it fills a single growable
+/// buffer and hands out batches of records (`RecordSet`) that reference byte spans
+/// within their own buffer rather than copying into individual `Record`s.
+pub struct RefReader<R: io::Read> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    eof: bool,
+}
+
+
+impl<R: io::Read> RefReader<R> {
+    /// Create a new `RefReader` wrapping the given `io::Read` instance.
+    pub fn new(reader: R) -> Self {
+        RefReader {
+            reader: reader,
+            buf: vec![0u8; REF_READER_INITIAL_BUFSIZE],
+            pos: 0,
+            filled: 0,
+            eof: false,
+        }
+    }
+
+    /// Fill `record_set` with up to `max_records` complete records, returning the number
+    /// of records parsed (`0` once the underlying reader is exhausted). The set's
+    /// previous contents are discarded.
+    pub fn read_record_set(&mut self, record_set: &mut RecordSet, max_records: usize) -> io::Result<usize> {
+        record_set.buffer.clear();
+        record_set.positions.clear();
+
+        loop {
+            let (positions, consumed) = scan_records(&self.buf[self.pos..self.filled], max_records, self.eof);
+
+            if !positions.is_empty() || self.eof {
+                if consumed > 0 {
+                    let start = self.pos;
+                    record_set.buffer.extend_from_slice(&self.buf[start..start + consumed]);
+                }
+                record_set.positions = positions;
+                self.pos += consumed;
+                return Ok(record_set.positions.len());
+            }
+
+            self.fill_buffer()?;
+        }
+    }
+
+    /// Read more data from the underlying reader, growing the buffer if a single record
+    /// doesn't fit, and compacting already-consumed bytes to the front first. `self.buf`
+    /// always keeps its full capacity as its length; only `self.filled` tracks how much of
+    /// it holds live data, so compacting never shrinks the "large read" granularity.
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.filled, 0);
+            self.filled -= self.pos;
+            self.pos = 0;
+        }
+
+        if self.filled == self.buf.len() {
+            let new_len = self.buf.len() * 2;
+            self.buf.resize(new_len, 0);
+        }
+
+        let n = self.reader.read(&mut self.buf[self.filled..])?;
+        if n == 0 {
+            self.eof = true;
+        } else {
+            self.filled += n;
+        }
+
+        Ok(())
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -610,30 +1363,38 @@ ATTGTTGTTTTA
         let mut seq = Vec::new();
 
         // Test reading various substrings of the sequence
-        reader.read("id", 1, 5, &mut seq).unwrap();
+        reader.fetch("id", 1, 5).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"CCGT");
 
-        reader.read("id", 1, 31, &mut seq).unwrap();
+        reader.fetch("id", 1, 31).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"CCGTAGGCTGACCGTAGGCTGAACGTAGGC");
 
-        reader.read("id", 13, 23, &mut seq).unwrap();
+        reader.fetch("id", 13, 23).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"CGTAGGCTGA");
 
-        reader.read("id", 36, 52, &mut seq).unwrap();
+        reader.fetch("id", 36, 52).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"GTAGGCTGAAAACCCC");
 
-        reader.read("id2", 12, 40, &mut seq).unwrap();
+        reader.fetch("id2", 12, 40).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"ATTGTTGTTTTAATTGTTGTTTTAGGGG");
 
-        reader.read("id2", 12, 12, &mut seq).unwrap();
+        reader.fetch("id2", 12, 12).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"");
 
-        reader.read("id2", 12, 13, &mut seq).unwrap();
+        reader.fetch("id2", 12, 13).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"A");
 
-        assert!(reader.read("id2", 12, 11, &mut seq).is_err());
-        assert!(reader.read("id2", 12, 1000, &mut seq).is_err());
-        assert!(reader.read("id3", 0, 1, &mut seq).is_err());
+        assert!(reader.fetch("id2", 12, 11).is_err());
+        assert!(reader.fetch("id2", 12, 1000).is_err());
+        assert!(reader.fetch("id3", 0, 1).is_err());
+        assert!(reader.read(&mut seq).is_err());
     }
 
     #[test]
@@ -643,10 +1404,44 @@ ATTGTTGTTTTA
                 .unwrap();
         let mut seq = Vec::new();
 
-        reader.read("id", 0, 16, &mut seq).unwrap();
+        reader.fetch("id", 0, 16).unwrap();
+        reader.read(&mut seq).unwrap();
         assert_eq!(seq, b"GTAGGCTGAAAACCCC");
     }
 
+    #[test]
+    fn test_indexed_reader_read_region() {
+        // One-shot convenience wrappers around fetch + read should behave identically to
+        // the decoupled calls they're built on.
+        let mut reader = IndexedReader::new(io::Cursor::new(FASTA_FILE), FAI_FILE).unwrap();
+        let mut seq = Vec::new();
+
+        reader.read_region("id", 1, 5, &mut seq).unwrap();
+        assert_eq!(seq, b"CCGT");
+
+        reader.read_region_all("id2", &mut seq).unwrap();
+        assert_eq!(seq, b"ATTGTTGTTTTAATTGTTGTTTTAATTGTTGTTTTAGGGG");
+
+        let bases: Vec<u8> = reader.read_region_iter("id", 1, 5).unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(bases, b"CCGT");
+
+        let bases: Vec<u8> = reader.read_region_iter_all("id2").unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(bases, b"ATTGTTGTTTTAATTGTTGTTTTAATTGTTGTTTTAGGGG");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_indexed_reader_deprecated_aliases() {
+        let mut reader = IndexedReader::new(io::Cursor::new(FASTA_FILE), FAI_FILE).unwrap();
+        let mut seq = Vec::new();
+
+        reader.read_all("id2", &mut seq).unwrap();
+        assert_eq!(seq, b"ATTGTTGTTTTAATTGTTGTTTTAATTGTTGTTTTAGGGG");
+
+        let bases: Vec<u8> = reader.read_iter_all("id2").unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(bases, b"ATTGTTGTTTTAATTGTTGTTTTAATTGTTGTTTTAGGGG");
+    }
+
     #[test]
     fn test_writer() {
         let mut writer = Writer::new(Vec::new());
@@ -655,4 +1450,151 @@ ATTGTTGTTTTA
         writer.flush().unwrap();
         assert_eq!(writer.writer.get_ref(), &WRITE_FASTA_FILE);
     }
+
+    #[test]
+    fn test_writer_line_width() {
+        let mut writer = Writer::new(Vec::new()).line_width(4);
+        writer.write("id", None, b"ACCGTAGGCTGA").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.writer.get_ref(), b">id\nACCG\nTAGG\nCTGA\n".as_ref());
+    }
+
+    #[test]
+    fn test_indexed_writer_matches_index_records() {
+        // The offset/line_bases/line_bytes IndexedWriter computes while writing should agree
+        // with what index_records derives by re-reading the same bytes, both unwrapped and
+        // wrapped to a line_width.
+        for &line_width in &[0usize, 4] {
+            let mut writer = IndexedWriter::new(Vec::new()).line_width(line_width);
+            writer.write("id", Some("desc"), b"ACCGTAGGCTGA").unwrap();
+            writer.write("id2", None, b"ATTGTTGTTTTA").unwrap();
+            writer.flush().unwrap();
+
+            let written = writer.writer.writer.get_ref().clone();
+            let expected = index_records(io::Cursor::new(written)).unwrap();
+
+            assert_eq!(writer.records.len(), expected.len());
+            for (&(ref name, ref record), &(ref expected_name, ref expected_record)) in
+                writer.records.iter().zip(expected.iter()) {
+                assert_eq!(name, expected_name);
+                assert_eq!(record.len, expected_record.len);
+                assert_eq!(record.offset, expected_record.offset);
+                assert_eq!(record.line_bases, expected_record.line_bases);
+                assert_eq!(record.line_bytes, expected_record.line_bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ref_reader_fill_buffer_keeps_capacity() {
+        // Compacting already-consumed bytes to the front must not shrink `buf` itself,
+        // only how much of it is reported as filled, or repeated straddling records would
+        // collapse the "large read" buffer toward the size of whatever was left over.
+        let mut reader = RefReader::new(FASTA_FILE);
+        reader.filled = 100;
+        reader.pos = 40;
+        let capacity_before = reader.buf.len();
+
+        reader.fill_buffer().unwrap();
+
+        assert_eq!(reader.buf.len(), capacity_before);
+    }
+
+    #[test]
+    fn test_compression_roundtrip() {
+        // Exercise `Writer::to_file_with_compression`/`Reader::from_file_with_compression`
+        // end to end, since the boxed `io::Write`/`io::Read` they return can't be
+        // inspected in-memory the way the uncompressed writer/reader can.
+        let mut path = std::env::temp_dir();
+        path.push("rust_bio_fasta_compression_roundtrip_test.fa.gz");
+
+        {
+            let mut writer = Writer::to_file_with_compression(&path).unwrap();
+            writer.write("id", Some("desc"), b"ACCGTAGGCTGA").unwrap();
+            writer.write("id2", None, b"ATTGTTGTTTTA").unwrap();
+            writer.flush().unwrap();
+        }
+
+        let reader = Reader::from_file_with_compression(&path).unwrap();
+        let ids = [Some("id"), Some("id2")];
+        let seqs: [&[u8]; 2] = [b"ACCGTAGGCTGA", b"ATTGTTGTTTTA"];
+
+        for (i, r) in reader.records().enumerate() {
+            let record = r.ok().expect("Error reading record");
+            assert_eq!(record.id(), ids[i]);
+            assert_eq!(record.seq(), seqs[i]);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ref_reader_record_set() {
+        // A record straddling the end of whatever was last read into the buffer can't be
+        // known complete until either the next record's `>` or EOF is seen, so a batch may
+        // come back short of `max_records`; keep pulling batches until the reader is dry.
+        let mut reader = RefReader::new(FASTA_FILE);
+        let mut record_set = RecordSet::new();
+        let mut ids = Vec::new();
+        let mut descs = Vec::new();
+        let mut seqs = Vec::new();
+
+        loop {
+            let n = reader.read_record_set(&mut record_set, 10).unwrap();
+            if n == 0 {
+                break;
+            }
+            for record in record_set.iter() {
+                ids.push(record.id().map(|s| s.to_owned()));
+                descs.push(record.desc().map(|s| s.to_owned()));
+                seqs.push(record.seq().into_owned());
+            }
+        }
+
+        assert_eq!(ids, vec![Some("id".to_owned()), Some("id2".to_owned())]);
+        assert_eq!(descs, vec![Some("desc".to_owned()), None]);
+        assert_eq!(seqs,
+                   vec![b"ACCGTAGGCTGACCGTAGGCTGAACGTAGGCTGAAAGTAGGCTGAAAACCCC".to_vec(),
+                        b"ATTGTTGTTTTAATTGTTGTTTTAATTGTTGTTTTAGGGG".to_vec()]);
+    }
+
+    #[test]
+    fn test_ref_record_to_owned_record() {
+        let mut reader = RefReader::new(FASTA_FILE);
+        let mut record_set = RecordSet::new();
+        reader.read_record_set(&mut record_set, 10).unwrap();
+
+        let owned = record_set.iter().next().unwrap().to_owned_record();
+        assert_eq!(owned.id(), Some("id"));
+        assert_eq!(owned.desc(), Some("desc"));
+        assert_eq!(owned.seq(),
+                   b"ACCGTAGGCTGACCGTAGGCTGAACGTAGGCTGAAAGTAGGCTGAAAACCCC".as_ref());
+    }
+
+    #[test]
+    fn test_index_records() {
+        let records = index_records(io::Cursor::new(FASTA_FILE)).unwrap();
+        let expected = Index::new(FAI_FILE).unwrap();
+
+        assert_eq!(records.len(), expected.inner.len());
+        for &(ref name, ref record) in &records {
+            let expected_record = expected.get(name).unwrap();
+            assert_eq!(record.len, expected_record.len);
+            assert_eq!(record.offset, expected_record.offset);
+            assert_eq!(record.line_bases, expected_record.line_bases);
+            assert_eq!(record.line_bytes, expected_record.line_bytes);
+        }
+    }
+
+    #[test]
+    fn test_index_records_rejects_overlong_last_line() {
+        // The last line of record "id" (90 bases) is longer than the 12-base width
+        // established by the earlier lines, which `samtools faidx` would reject.
+        let fasta: &[u8] = b">id\n\
+ACCGTAGGCTGA\n\
+CCGTAGGCTGAA\n\
+AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n";
+
+        assert!(index_records(io::Cursor::new(fasta)).is_err());
+    }
 }